@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A minimal layered INI-style config for `openship.conf`.
+//
+// Recognised line shapes:
+//   section header   ^[([^\]]+)]
+//   key = value item ^([^=\s][^=]*?)\s*=\s*(.*\S)?
+//   comment / blank  ^(;|#|\s*$)
+// Two directives are special-cased: `%include <path>` parses another file
+// (relative to the including file's directory) and merges it in as a later
+// layer, and `%unset <section>.<key>` removes `key`, whether it was set by
+// an earlier layer or is one of the hardcoded defaults.
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    extensions: HashMap<String, String>,
+    scopes: HashSet<String>,
+}
+
+enum Entry {
+    Set(String, String),
+    Unset(String),
+}
+
+impl Config {
+    /// Bucketing rules in effect before config support existed.
+    fn defaults() -> Config {
+        let mut extensions = HashMap::new();
+        for ext in ["js", "jsx", "ts", "tsx"] {
+            extensions.insert(ext.to_string(), "::js".to_string());
+        }
+        extensions.insert("css".to_string(), "::css".to_string());
+        let mut scopes = HashSet::new();
+        scopes.insert("dep".to_string());
+        Config { extensions, scopes }
+    }
+
+    /// Load the layered config rooted at `path`. Missing files (the root or
+    /// any `%include`) are silently treated as an empty layer, so a run
+    /// with no `openship.conf` falls back to the hardcoded defaults.
+    pub fn load(path: &Path) -> Config {
+        let mut entries = vec![];
+        collect_entries(path, &mut entries);
+
+        let mut flat: HashMap<String, String> = HashMap::new();
+        let mut unset: HashSet<String> = HashSet::new();
+        for entry in entries {
+            match entry {
+                Entry::Set(key, value) => {
+                    unset.remove(&key);
+                    flat.insert(key, value);
+                }
+                Entry::Unset(key) => {
+                    flat.remove(&key);
+                    unset.insert(key);
+                }
+            }
+        }
+
+        let mut config = Config::defaults();
+        // Unsets apply to the hardcoded defaults too, not just the layers
+        // loaded above, so a key can be suppressed outright.
+        for key in &unset {
+            if let Some(ext) = key.strip_prefix("extensions.") {
+                config.extensions.remove(ext);
+            } else if let Some(scope) = key.strip_prefix("scopes.") {
+                config.scopes.remove(scope);
+            }
+        }
+        for (key, value) in flat {
+            if let Some(ext) = key.strip_prefix("extensions.") {
+                config.extensions.insert(ext.to_string(), value);
+            } else if let Some(scope) = key.strip_prefix("scopes.") {
+                config.scopes.insert(scope.to_string());
+            }
+        }
+        config
+    }
+
+    /// The bucket suffix `Graph::mangle` should append for `extension`, if any.
+    pub fn bucket_for(&self, extension: &str) -> Option<&str> {
+        self.extensions.get(extension).map(|s| s.as_str())
+    }
+
+    /// Whether `scope` opts out of extension mangling entirely (like `dep`).
+    pub fn skips_extension_mangling(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::defaults()
+    }
+}
+
+fn collect_entries(path: &Path, into: &mut Vec<Entry>) {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    let dir: PathBuf = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let mut section = String::new();
+
+    for raw_line in contents.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("%include") {
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                let include_path = dir.join(rest.trim());
+                collect_entries(&include_path, into);
+                continue;
+            }
+        }
+        if let Some(rest) = line.strip_prefix("%unset") {
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                // Unlike a bare `key = value` item, the argument here is
+                // already a full `section.key` (e.g. `%unset extensions.css`),
+                // so it isn't re-qualified with the current section.
+                let key = rest.trim();
+                if !key.is_empty() {
+                    into.push(Entry::Unset(key.to_string()));
+                }
+                continue;
+            }
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_string();
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            let value = value.trim();
+            if !key.is_empty() {
+                into.push(Entry::Set(format!("{}.{}", section, key), value.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("openship_config_test_{}", name));
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_defaults_when_missing() {
+        let config = Config::load(Path::new("/nonexistent/openship.conf"));
+        assert_eq!(config.bucket_for("js"), Some("::js"));
+        assert_eq!(config.bucket_for("css"), Some("::css"));
+        assert!(config.skips_extension_mangling("dep"));
+        assert!(!config.skips_extension_mangling("app"));
+    }
+
+    #[test]
+    fn test_extra_extensions_and_scopes() {
+        let path = write_temp(
+            "extras.conf",
+            "[extensions]\nscss = ::css\nmjs = ::js\n\n[scopes]\nvirtual =\n",
+        );
+        let config = Config::load(&path);
+        assert_eq!(config.bucket_for("scss"), Some("::css"));
+        assert_eq!(config.bucket_for("mjs"), Some("::js"));
+        assert!(config.skips_extension_mangling("virtual"));
+        assert!(config.skips_extension_mangling("dep")); // default still present
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_key_prefixed_like_directive_is_not_consumed() {
+        let path = write_temp(
+            "directive_prefix.conf",
+            "[extensions]\nincludes = ::weird\nunsettle = ::js\n",
+        );
+        let config = Config::load(&path);
+        assert_eq!(config.bucket_for("includes"), Some("::weird"));
+        assert_eq!(config.bucket_for("unsettle"), Some("::js"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_include_and_unset_layering() {
+        let included = write_temp("included.conf", "[extensions]\nvue = ::js\n");
+        let main = write_temp(
+            "main.conf",
+            &format!(
+                "[extensions]\ncss = ::weird\n%include {}\n%unset extensions.css\n",
+                included.file_name().unwrap().to_str().unwrap()
+            ),
+        );
+        let config = Config::load(&main);
+        // %unset removed both the override and the hardcoded default.
+        assert_eq!(config.bucket_for("css"), None);
+        // The include layer applied on top of the main file's own settings.
+        assert_eq!(config.bucket_for("vue"), Some("::js"));
+        let _ = fs::remove_file(&main);
+        let _ = fs::remove_file(&included);
+    }
+
+    #[test]
+    fn test_unset_can_suppress_a_hardcoded_default() {
+        let path = write_temp("unset_default.conf", "%unset scopes.dep\n");
+        let config = Config::load(&path);
+        assert!(!config.skips_extension_mangling("dep"));
+        let _ = fs::remove_file(&path);
+    }
+}
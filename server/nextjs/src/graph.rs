@@ -1,4 +1,6 @@
-use crate::hash::{AbsHash, ImplHash};
+use crate::config::Config;
+use crate::hash::{is_current_abs_hash, AbsHash, ImplHash};
+use crate::sketch::jaccard_estimate;
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Serialize, Serializer};
 use std::collections::{HashMap, HashSet};
@@ -25,11 +27,47 @@ pub struct Node {
     pub i: Option<ImplHash>,
     pub d: HashSet<Mangled>,
     pub v: Option<Version>,
+    // Bottom-k MinHash sketch of the module's source, for near-duplicate
+    // detection via `Graph::similar`. `Option` keeps older graph files
+    // (written before this field existed) readable.
+    pub m: Option<Vec<u64>>,
 }
 
 pub struct Graph {
     existing: HashMap<Mangled, Node>,
     new: HashMap<Mangled, Node>,
+    config: Config,
+    // Reverse index: dep -> keys of nodes that declare `dep` in their `d` set.
+    dependents: HashMap<Mangled, HashSet<Mangled>>,
+    // Keys waiting on a dep that was missing or lacked an impl hash, so they
+    // can be revisited once that dep later appears (see `Graph::defer_on`).
+    pending: HashMap<Mangled, HashSet<Mangled>>,
+}
+
+#[derive(Serialize)]
+pub struct Edge {
+    pub from: Mangled,
+    pub to: Mangled,
+}
+
+/// Single self-describing JSON document for external tooling: every module
+/// plus its dependency edges flattened out of each node's `d` set, and the
+/// `roots` — modules nothing else in the graph depends on.
+#[derive(Serialize)]
+pub struct GraphDoc {
+    pub schema: u32,
+    pub roots: Vec<Mangled>,
+    pub modules: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// What changed between two `Graph`s: modules only the latter has, modules
+/// only the former has, and modules present in both whose `ImplHash` differs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GraphDiff {
+    pub added: Vec<Mangled>,
+    pub removed: Vec<Mangled>,
+    pub changed: Vec<Mangled>,
 }
 
 impl Serialize for Graph {
@@ -46,20 +84,45 @@ impl Serialize for Graph {
 
 impl Graph {
     #[allow(dead_code)]
-    pub fn new() -> Graph {
+    pub fn new(config: Config) -> Graph {
         let existing = HashMap::new();
         let new = HashMap::new();
-        Graph { existing, new }
+        Graph {
+            existing,
+            new,
+            config,
+            dependents: HashMap::new(),
+            pending: HashMap::new(),
+        }
     }
-    pub fn read_graph<'a>(it: impl Iterator<Item = &'a str>) -> serde_json::Result<Graph> {
+    pub fn read_graph<'a>(
+        it: impl Iterator<Item = &'a str>,
+        config: Config,
+    ) -> serde_json::Result<Graph> {
         let mut existing = HashMap::new();
         for line in it {
             let node: Node = serde_json::from_str(&line)?;
-            let key = Self::mangle(&node.s, &node.o, &node.e);
+            if !is_current_abs_hash(&node.a) {
+                // Stale entry from a previous hashing scheme: drop it so it
+                // can't be mistaken for (or collide with) a current one.
+                continue;
+            }
+            let key = Self::mangle_with(&node.s, &node.o, &node.e, &config);
             existing.insert(key, node);
         }
-        let new = HashMap::new();
-        Ok(Graph { existing, new })
+        let mut dependents: HashMap<Mangled, HashSet<Mangled>> = HashMap::new();
+        for (key, node) in &existing {
+            for dep in &node.d {
+                dependents.entry(dep.clone()).or_default().insert(key.clone());
+            }
+        }
+        Ok(Graph {
+            existing,
+            new: HashMap::new(),
+            config,
+            dependents,
+            pending: HashMap::new(),
+        })
     }
     pub fn write_graph(&self) -> Vec<String> {
         self.new
@@ -68,54 +131,150 @@ impl Graph {
             .collect() // How can to_json fail?
     }
     pub fn get(&mut self, k: &Mangled) -> Option<&Node> {
+        self.lookup(k)
+    }
+    fn lookup(&self, k: &Mangled) -> Option<&Node> {
         match self.new.get(k) {
             Some(v) => Some(v),
             None => self.existing.get(k),
         }
     }
     pub fn insert(&mut self, v: Node) -> bool {
-        let k = Self::mangle(&v.s, &v.o, &v.e);
-        match self.get(&k) {
+        let k = self.mangle(&v.s, &v.o, &v.e);
+        let previous_deps = match self.get(&k) {
             Some(v0) => {
                 if v == *v0 {
                     return false;
                 }
+                Some(v0.d.clone())
             }
-            None => (),
+            None => None,
         };
+        if let Some(previous_deps) = previous_deps {
+            for dep in previous_deps.difference(&v.d) {
+                if let Some(dependents) = self.dependents.get_mut(dep) {
+                    dependents.remove(&k);
+                }
+            }
+        }
+        for dep in &v.d {
+            self.dependents.entry(dep.clone()).or_default().insert(k.clone());
+        }
         self.new.insert(k, v);
         true
     }
-    pub fn find_with_dep<'a>(&'a self, dep: Mangled) -> Vec<&'a Node> {
-        let mut vec: Vec<&'a Node> = vec![];
-        for (_, n) in &self.new {
-            if n.d.get(&dep).is_some() {
-                vec.push(n);
-            }
-        }
-        for (_, n) in &self.existing {
-            if n.d.get(&dep).is_some() {
-                vec.push(n);
-            }
+    /// O(1) reverse lookup: keys of nodes that declare `dep` as a dependency.
+    pub fn find_with_dep(&self, dep: &Mangled) -> Vec<Mangled> {
+        match self.dependents.get(dep) {
+            Some(keys) => keys.iter().cloned().collect(),
+            None => vec![],
         }
-        vec
+    }
+    /// Record that `waiter` couldn't compute its impl hash because `dep` was
+    /// missing or incomplete, so it can be revisited once `dep` resolves.
+    pub fn defer_on(&mut self, dep: Mangled, waiter: Mangled) {
+        self.pending.entry(dep).or_default().insert(waiter);
+    }
+    /// Take (and clear) the keys waiting on `dep`, now that it has resolved.
+    pub fn take_pending(&mut self, dep: &Mangled) -> HashSet<Mangled> {
+        self.pending.remove(dep).unwrap_or_default()
     }
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.existing.len() + self.new.len()
     }
-    pub fn mangle(scope: &Scope, object: &Object, extension: &Option<Extension>) -> Mangled {
+    /// Nodes whose MinHash sketch is at least `threshold` similar to `key`'s,
+    /// estimated by `jaccard_estimate`. Nodes without a sketch (or `key`
+    /// itself) never match.
+    #[allow(dead_code)]
+    pub fn similar(&self, key: &Mangled, threshold: f64) -> Vec<&Node> {
+        let target = match self.lookup(key).and_then(|n| n.m.as_ref()) {
+            Some(m) => m,
+            None => return vec![],
+        };
+        self.new
+            .values()
+            .chain(self.existing.values())
+            .filter(|n| {
+                if &self.mangle(&n.s, &n.o, &n.e) == key {
+                    return false;
+                }
+                match &n.m {
+                    Some(sketch) => jaccard_estimate(target, sketch) >= threshold,
+                    None => false,
+                }
+            })
+            .collect()
+    }
+    // `new` entries shadow `existing` ones with the same key, same as `lookup`.
+    fn all_nodes(&self) -> HashMap<&Mangled, &Node> {
+        let mut nodes: HashMap<&Mangled, &Node> = self.existing.iter().collect();
+        nodes.extend(self.new.iter());
+        nodes
+    }
+    #[allow(dead_code)]
+    pub fn to_document(&self) -> GraphDoc {
+        let nodes = self.all_nodes();
+        let mut modules = Vec::with_capacity(nodes.len());
+        let mut edges = vec![];
+        for (&key, &node) in &nodes {
+            modules.push(node.clone());
+            for dep in &node.d {
+                edges.push(Edge {
+                    from: key.clone(),
+                    to: dep.clone(),
+                });
+            }
+        }
+        let roots = nodes
+            .keys()
+            .filter(|k| self.dependents.get(**k).map_or(true, |deps| deps.is_empty()))
+            .map(|&k| k.clone())
+            .collect();
+        GraphDoc {
+            schema: 1,
+            roots,
+            modules,
+            edges,
+        }
+    }
+    pub fn diff(&self, other: &Graph) -> GraphDiff {
+        let before = self.all_nodes();
+        let after = other.all_nodes();
+
+        let mut diff = GraphDiff::default();
+        for (&key, &node) in &after {
+            match before.get(key) {
+                None => diff.added.push(key.clone()),
+                Some(&prev) if prev.i != node.i => diff.changed.push(key.clone()),
+                Some(_) => (),
+            }
+        }
+        for &key in before.keys() {
+            if !after.contains_key(key) {
+                diff.removed.push(key.clone());
+            }
+        }
+        diff
+    }
+    pub fn mangle(&self, scope: &Scope, object: &Object, extension: &Option<Extension>) -> Mangled {
+        Self::mangle_with(scope, object, extension, &self.config)
+    }
+    fn mangle_with(
+        scope: &Scope,
+        object: &Object,
+        extension: &Option<Extension>,
+        config: &Config,
+    ) -> Mangled {
         let Scope(scope) = scope;
         let Object(object) = object;
         let mut ext = "";
-        if scope == "dep" {
-            // Pass
-        } else {
-            ext = match extension {
-                Some(Extension(e)) if e == "js" || e == "jsx" || e == "ts" || e == "tsx" => "::js",
-                Some(Extension(e)) if e == "css" => "::css",
-                _ => "".into(),
-            };
+        if !config.skips_extension_mangling(scope) {
+            if let Some(Extension(e)) = extension {
+                if let Some(bucket) = config.bucket_for(e) {
+                    ext = bucket;
+                }
+            }
         }
         Mangled(format!("{}::{}{}", scope, object, ext))
     }
@@ -124,14 +283,81 @@ impl Graph {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sketch::minhash_sketch;
+
+    fn make_node(object: &str, scope: &str, abs: &str, code: &str) -> Node {
+        Node {
+            o: Object(object.to_string()),
+            s: Scope(scope.to_string()),
+            e: None,
+            a: AbsHash(abs.to_string()),
+            i: None,
+            d: HashSet::new(),
+            v: None,
+            m: Some(minhash_sketch(code)),
+        }
+    }
+
+    #[test]
+    fn test_similar_finds_near_duplicates_only() {
+        let mut g = Graph::new(Config::default());
+        let code = "const a = 1; function f(x) { return x + a; }";
+        let key_a = g.mangle(&Scope("s1".into()), &Object("a".into()), &None);
+        g.insert(make_node("a", "s1", "osha_2a", code));
+        g.insert(make_node("b", "s1", "osha_2b", code));
+        g.insert(make_node(
+            "c",
+            "s1",
+            "osha_2c",
+            "export default class Widget extends Base { render() {} }",
+        ));
+
+        let matches = g.similar(&key_a, 0.5);
+        let matched_objects: HashSet<String> =
+            matches.iter().map(|n| n.o.0.clone()).collect();
+        assert_eq!(matched_objects, HashSet::from(["b".to_string()]));
+    }
+
+    #[test]
+    fn test_similar_ignores_nodes_without_sketch() {
+        let mut g = Graph::new(Config::default());
+        let code = "const a = 1; function f(x) { return x + a; }";
+        let key_a = g.mangle(&Scope("s1".into()), &Object("a".into()), &None);
+        g.insert(make_node("a", "s1", "osha_2a", code));
+        let mut no_sketch = make_node("b", "s1", "osha_2b", code);
+        no_sketch.m = None;
+        g.insert(no_sketch);
+
+        assert!(g.similar(&key_a, 0.5).is_empty());
+    }
+
+    #[test]
+    fn test_to_document_flattens_edges_and_roots() {
+        let mut g = Graph::new(Config::default());
+        let dep = make_node("dep", "s1", "osha_2d", "const x = 1;");
+        let mut root = make_node("root", "s1", "osha_2r", "const y = 2;");
+        root.d.insert(g.mangle(&Scope("s1".into()), &Object("dep".into()), &None));
+        g.insert(dep);
+        g.insert(root);
+
+        let doc = g.to_document();
+        assert_eq!(doc.schema, 1);
+        assert_eq!(doc.modules.len(), 2);
+        assert_eq!(doc.edges.len(), 1);
+        let key_root = g.mangle(&Scope("s1".into()), &Object("root".into()), &None);
+        let key_dep = g.mangle(&Scope("s1".into()), &Object("dep".into()), &None);
+        assert_eq!(doc.edges[0].from, key_root);
+        assert_eq!(doc.edges[0].to, key_dep);
+        assert_eq!(doc.roots, vec![key_root]);
+    }
 
     #[test]
 
     fn test_read_graph() {
-        let json_data = r#"{ "v": null, "o": "o1", "e": null, "s": "s1", "a": "123", "d": [], "l": [] }
-        { "v": null, "o": "o2", "e": "ts", "s": "s2", "a": "456", "d": [], "l": ["AppDir"] }
-        { "v": null, "o": "o2", "e": "tsx", "s": "s2", "a": "456", "d": [], "l": ["AppDir"] }"#;
-        let r = Graph::read_graph(json_data.lines());
+        let json_data = r#"{ "v": null, "o": "o1", "e": null, "s": "s1", "a": "osha_2123", "d": [], "l": [] }
+        { "v": null, "o": "o2", "e": "ts", "s": "s2", "a": "osha_2456", "d": [], "l": ["AppDir"] }
+        { "v": null, "o": "o2", "e": "tsx", "s": "s2", "a": "osha_2456", "d": [], "l": ["AppDir"] }"#;
+        let r = Graph::read_graph(json_data.lines(), Config::default());
         assert!(r.is_ok());
         assert_eq!(r.unwrap().len(), 2);
     }
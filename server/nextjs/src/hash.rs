@@ -1,9 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::hash::{DefaultHasher, Hash, Hasher};
 use swc_core::ecma::ast::Program;
 
 use crate::graph::Mangled;
+use crate::program_to_string;
 
 // Abstract Hash
 #[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -12,6 +12,34 @@ pub struct AbsHash(pub String);
 #[derive(PartialEq, Eq, Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct ImplHash(pub String);
 
+// Bumped whenever the hashing scheme changes, so entries from an older
+// scheme can be told apart from current ones instead of silently
+// colliding (e.g. in `Graph::read_graph`).
+const ABS_VERSION: &str = "osha_2";
+const IMPL_VERSION: &str = "oshi_2";
+
+/// Whether `hash` was produced by the current abstract-hash scheme, as
+/// opposed to a stale entry left over from an older one.
+pub fn is_current_abs_hash(hash: &AbsHash) -> bool {
+    let AbsHash(s) = hash;
+    s.starts_with(ABS_VERSION)
+}
+
+// FNV-1a: fixed, portable, and independent of `std`'s SipHash internals or
+// any derived `Hash` impl, so the digest means the same thing across Rust
+// and swc versions.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub(crate) fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
 fn u64_to_hash(num: u64) -> String {
     const ALPHABET: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
     const BASE: u64 = ALPHABET.len() as u64;
@@ -42,32 +70,36 @@ fn u64_to_hash(num: u64) -> String {
 }
 
 pub fn program_hash(program: &Program) -> AbsHash {
-    let mut hasher = DefaultHasher::new();
-    program.hash(&mut hasher);
-    let hash = u64_to_hash(hasher.finish());
-    AbsHash(format!("osha_1{hash}"))
+    // Hash the canonicalized (re-emitted) source rather than the AST itself,
+    // so the digest doesn't depend on swc's derived `Hash` field layout.
+    let code = program_to_string(program);
+    let hash = u64_to_hash(fnv1a_64(code.as_bytes()));
+    AbsHash(format!("{ABS_VERSION}{hash}"))
 }
 
 pub fn program_impl_hash(abs_hash: &AbsHash, deps: HashMap<Mangled, ImplHash>) -> ImplHash {
-    let mut hasher = DefaultHasher::new();
+    // Sort so iteration order of the `HashMap` can't change the result.
+    let mut deps: Vec<(&Mangled, &ImplHash)> = deps.iter().collect();
+    deps.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
     let AbsHash(abs_hash_str) = abs_hash;
-    abs_hash_str.hash(&mut hasher);
-    for (key, value) in deps.iter() {
-        key.hash(&mut hasher);
-        value.hash(&mut hasher);
+    let mut buf = abs_hash_str.clone();
+    for (key, value) in deps {
+        buf.push('\0');
+        buf.push_str(&key.0);
+        buf.push('\0');
+        buf.push_str(&value.0);
     }
-    let hash = u64_to_hash(hasher.finish());
-    ImplHash(format!("oshi_1{hash}"))
+    let hash = u64_to_hash(fnv1a_64(buf.as_bytes()));
+    ImplHash(format!("{IMPL_VERSION}{hash}"))
 }
 
 pub fn depencency_hash(name: &str, version: &str) -> (AbsHash, ImplHash) {
-    let mut hasher = DefaultHasher::new();
-    name.hash(&mut hasher);
-    version.hash(&mut hasher);
-    let hash = u64_to_hash(hasher.finish());
+    let buf = format!("{name}\0{version}");
+    let hash = u64_to_hash(fnv1a_64(buf.as_bytes()));
     (
-        AbsHash(format!("osha_1{hash}")),
-        ImplHash(format!("oshi_1{hash}")),
+        AbsHash(format!("{ABS_VERSION}{hash}")),
+        ImplHash(format!("{IMPL_VERSION}{hash}")),
     )
 }
 
@@ -92,7 +124,7 @@ mod tests {
         assert_eq!(u64_to_hash(0), "8GxynqChlO7");
         assert_eq!(
             program_hash(&program),
-            AbsHash("osha_1ixxfAnWr3K4".to_string())
+            AbsHash("osha_2eGH8OnpqlgU".to_string())
         );
     }
 }
@@ -1,5 +1,5 @@
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::thread;
@@ -17,12 +17,16 @@ use swc_core::plugin::{
 };
 use swc_ecma_parser::{error::Error as SwcError, lexer::Lexer, EsSyntax, Parser, Syntax};
 
+mod config;
+use config::Config;
 mod graph;
 use graph::{Extension, Graph, Mangled, Node, Object, Scope, Version};
 mod hash;
 use hash::{depencency_hash, program_hash, program_impl_hash, AbsHash, ImplHash};
 mod path;
 use path::format_dependency;
+mod sketch;
+use sketch::minhash_sketch;
 
 fn load_package(path: &str) -> Result<(String, String), io::Error> {
     let full_path = format!("/cwd/{}/package.json", path);
@@ -36,7 +40,7 @@ fn load_package(path: &str) -> Result<(String, String), io::Error> {
     Err(io::Error::new(io::ErrorKind::NotFound, "Version not found"))
 }
 
-fn program_to_string(program: &Program) -> String {
+pub(crate) fn program_to_string(program: &Program) -> String {
     let mut buf = vec![];
     let cm: Lrc<SourceMap> = Default::default();
     let writer = JsWriter::new(cm.clone(), "\n", &mut buf, None);
@@ -139,44 +143,66 @@ fn lines_to_file(path: &Path, append: bool, lines: Vec<String>) -> io::Result<Ve
     Ok(lines)
 }
 
-fn handle_node(graph: &mut Graph, mut node: Node) {
-    // Downstream first
-    let deps = &node.d;
-    let mut deps_map = HashMap::<Mangled, ImplHash>::new();
-    let mut can_oshi = true;
-    for dep in deps {
-        match graph.get(&dep) {
-            Some(n) => match &n.i {
-                Some(oshi) => {
-                    deps_map.insert(dep.clone(), oshi.clone());
-                }
+// Explicit worklist instead of recursion: a circular import (A imports B
+// imports A) would otherwise recurse into `handle_node` forever. `visited`
+// caps each node to one recompute per call, so cycles just settle instead
+// of looping.
+fn handle_node(graph: &mut Graph, node: Node) {
+    let mut queue: VecDeque<Node> = VecDeque::from([node]);
+    let mut visited: HashSet<Mangled> = HashSet::new();
+
+    while let Some(mut current) = queue.pop_front() {
+        let mangled = graph.mangle(&current.s, &current.o, &current.e);
+        if !visited.insert(mangled.clone()) {
+            continue;
+        }
+
+        let mut deps_map = HashMap::<Mangled, ImplHash>::new();
+        let mut can_oshi = true;
+        for dep in &current.d {
+            match graph.get(dep) {
+                Some(n) => match &n.i {
+                    Some(oshi) => {
+                        deps_map.insert(dep.clone(), oshi.clone());
+                    }
+                    None => {
+                        can_oshi = false;
+                        println!("DEP {:?} no oshi", dep);
+                    }
+                },
                 None => {
                     can_oshi = false;
-                    println!("DEP {:?} no oshi", dep);
+                    println!("DEQ {:?} not found", dep);
+                }
+            };
+        }
+        println!("HAN {:?} {}", current.o, can_oshi);
+        if can_oshi {
+            current.i = Some(program_impl_hash(&current.a, deps_map));
+        } else {
+            // Leave `i` unset and come back to this node once the missing
+            // (or not-yet-hashed) dependency resolves, instead of dropping
+            // the propagation on the floor.
+            current.i = None;
+            for dep in &current.d {
+                if graph.get(dep).and_then(|n| n.i.as_ref()).is_none() {
+                    graph.defer_on(dep.clone(), mangled.clone());
+                }
+            }
+        }
+
+        let changed = graph.insert(current);
+        if changed {
+            let mut revisit: Vec<Mangled> = graph.find_with_dep(&mangled);
+            revisit.extend(graph.take_pending(&mangled));
+            for key in revisit {
+                if visited.contains(&key) {
+                    continue;
+                }
+                if let Some(n) = graph.get(&key) {
+                    queue.push_back(n.clone());
                 }
-            },
-            None => {
-                can_oshi = false;
-                println!("DEQ {:?} not found", dep);
             }
-        };
-    }
-    println!("HAN {:?} {}", node.o, can_oshi);
-    if can_oshi {
-        let impl_hash = program_impl_hash(&node.a, deps_map);
-        node.i = Some(impl_hash);
-    };
-    let mangled = Graph::mangle(&node.s, &node.o, &node.e);
-    let inserted = graph.insert(node);
-    // Propagate to upstream nodes
-    if inserted {
-        let nodes: Vec<Node> = graph
-            .find_with_dep(mangled)
-            .iter()
-            .map(|&node| node.clone())
-            .collect();
-        for n in nodes {
-            handle_node(graph, n.clone());
         }
     }
 }
@@ -193,9 +219,12 @@ fn process(
         Err(err) => return Err(err),
         Ok(_) => (),
     };
+    let config_path = Path::new("/cwd/.next/openship/openship.conf");
+    let config = Config::load(config_path);
     let graph_path = Path::new("/cwd/.next/openship/graph");
     let graph_lines = lines_from_file(graph_path)?;
-    let mut graph = Graph::read_graph(graph_lines.iter().map(|l| l.as_str()))?;
+    let before = Graph::read_graph(graph_lines.iter().map(|l| l.as_str()), config.clone())?;
+    let mut graph = Graph::read_graph(graph_lines.iter().map(|l| l.as_str()), config)?;
 
     let file_name = metadata
         .get_context(&TransformPluginMetadataContextKind::Filename)
@@ -233,6 +262,7 @@ fn process(
                         i: Some(impl_hash),
                         d: HashSet::new(),
                         v: version,
+                        m: None,
                     };
                     graph.insert(node);
                 }
@@ -252,9 +282,11 @@ fn process(
                 .iter()
                 .map(|i| {
                     let (scope, object, extension) = format_dependency(&dir, &i);
-                    Graph::mangle(&scope, &object, &extension)
+                    graph.mangle(&scope, &object, &extension)
                 })
                 .collect();
+            let program_string = program_to_string(&program);
+            let sketch = Some(minhash_sketch(&program_string));
             let node = Node {
                 o: object,
                 e: extension,
@@ -263,15 +295,24 @@ fn process(
                 i: None,
                 d,
                 v: version,
+                m: sketch,
             };
             handle_node(&mut graph, node);
-            let program_string = program_to_string(&program);
             let AbsHash(hash_string) = abstract_hash;
             let path = format!("/cwd/.next/openship/{}", hash_string);
             let path = Path::new(&path);
             let _ = lines_to_file(&path, false, vec![program_string]);
         };
     };
+    let diff = before.diff(&graph);
+    if !diff.added.is_empty() || !diff.removed.is_empty() || !diff.changed.is_empty() {
+        println!(
+            "DIF +{} -{} ~{}",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+    }
     match lines_to_file(graph_path, true, graph.write_graph()) {
         Ok(_) => (),
         Err(err) => println!("XEE {}", err),
@@ -304,62 +345,101 @@ mod tests {
             i: None,
             d: deps.into_iter().collect(),
             v: None,
+            m: None,
         }
     }
 
     #[test]
     fn test_handle_node_no_deps() {
-        let mut graph = Graph::new();
+        let mut graph = Graph::new(Config::default());
         let node = create_test_node("A", vec![]);
 
         handle_node(&mut graph, node.clone());
 
         let handled = graph
-            .get(&Graph::mangle(&node.s, &node.o, &node.e))
+            .get(&graph.mangle(&node.s, &node.o, &node.e))
             .unwrap();
         assert!(handled.i.is_some());
     }
 
     // #[test]
     // fn test_handle_node_with_missing_dep() {
-    //     let mut graph = Graph::new();
+    //     let mut graph = Graph::new(Config::default());
     //     let dep_mangled = Graph::mangle(&Scope("".to_string()), &Object("B".to_string()));
     //     let node = create_test_node("A", vec![dep_mangled]);
 
     //     handle_node(&mut graph, node.clone());
 
-    //     let handled = graph.get(&Graph::mangle(&node.s, &node.o, &node.e)).unwrap();
+    //     let handled = graph.get(&graph.mangle(&node.s, &node.o, &node.e)).unwrap();
     //     assert!(handled.i.is_none());
     // }
 
     #[test]
     fn test_handle_node_with_existing_dep() {
-        let mut graph = Graph::new();
+        let mut graph = Graph::new(Config::default());
         let dep_node = create_test_node("B", vec![]);
         graph.insert(dep_node.clone());
         handle_node(&mut graph, dep_node.clone()); // Ensure B has an impl hash
 
-        let dep_mangled = Graph::mangle(&dep_node.s, &dep_node.o, &dep_node.e);
+        let dep_mangled = graph.mangle(&dep_node.s, &dep_node.o, &dep_node.e);
         let node = create_test_node("A", vec![dep_mangled]);
 
         handle_node(&mut graph, node.clone());
 
         let handled = graph
-            .get(&Graph::mangle(&node.s, &node.o, &node.e))
+            .get(&graph.mangle(&node.s, &node.o, &node.e))
             .unwrap();
         assert!(handled.i.is_some());
     }
 
+    #[test]
+    fn test_handle_node_cycle_terminates() {
+        let mut graph = Graph::new(Config::default());
+
+        let key_a = graph.mangle(&Scope("".into()), &Object("A".into()), &Some(Extension("js".into())));
+        let key_b = graph.mangle(&Scope("".into()), &Object("B".into()), &Some(Extension("js".into())));
+        let node_a = create_test_node("A", vec![key_b.clone()]);
+        let node_b = create_test_node("B", vec![key_a.clone()]);
+        graph.insert(node_b);
+
+        // Neither side can ever compute an impl hash in a two-node cycle, so
+        // this only needs to return (rather than loop forever chasing A -> B
+        // -> A -> ...) for the test to pass.
+        handle_node(&mut graph, node_a);
+
+        assert!(graph.get(&key_a).unwrap().i.is_none());
+        assert!(graph.get(&key_b).unwrap().i.is_none());
+    }
+
+    #[test]
+    fn test_handle_node_revisits_once_missing_dep_resolves() {
+        let mut graph = Graph::new(Config::default());
+
+        let dep_mangled = graph.mangle(&Scope("".into()), &Object("B".into()), &Some(Extension("js".into())));
+        let node_a = create_test_node("A", vec![dep_mangled]);
+        handle_node(&mut graph, node_a.clone());
+
+        let key_a = graph.mangle(&node_a.s, &node_a.o, &node_a.e);
+        // B doesn't exist yet, so A is deferred rather than resolved.
+        assert!(graph.get(&key_a).unwrap().i.is_none());
+
+        let node_b = create_test_node("B", vec![]);
+        handle_node(&mut graph, node_b);
+
+        // Handling B should pull A back off `pending` and fill in its hash.
+        assert!(graph.get(&key_a).unwrap().i.is_some());
+    }
+
     #[test]
     fn test_handle_node_update_propagation() {
-        let mut graph = Graph::new();
+        let mut graph = Graph::new(Config::default());
 
         // Create and handle B first
         let node_b = create_test_node("B", vec![]);
         handle_node(&mut graph, node_b.clone());
 
         // Create A depending on B
-        let dep_mangled = Graph::mangle(&node_b.s, &node_b.o, &node_b.e);
+        let dep_mangled = graph.mangle(&node_b.s, &node_b.o, &node_b.e);
         let node_a = create_test_node("A", vec![dep_mangled]);
         graph.insert(node_a.clone());
 
@@ -370,7 +450,7 @@ mod tests {
 
         // Check if A's impl hash has changed
         let updated_a = graph
-            .get(&Graph::mangle(&node_a.s, &node_a.o, &node_a.e))
+            .get(&graph.mangle(&node_a.s, &node_a.o, &node_a.e))
             .unwrap();
         assert!(updated_a.i.is_some());
         assert_ne!(updated_a.i, node_a.i);
@@ -0,0 +1,72 @@
+use crate::hash::fnv1a_64;
+
+// Bottom-k MinHash sketches for near-duplicate module detection: a fixed-size
+// summary that lets `Graph::similar` estimate Jaccard similarity between two
+// modules without ever comparing their full source.
+
+const SHINGLE_SIZE: usize = 5;
+pub const SKETCH_SIZE: usize = 64;
+
+fn tokenize(code: &str) -> Vec<&str> {
+    code.split(|c: char| c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Build a bottom-k sketch from the `k` smallest distinct hashes of the
+/// code's overlapping `SHINGLE_SIZE`-token shingles.
+pub fn minhash_sketch(code: &str) -> Vec<u64> {
+    let tokens = tokenize(code);
+    let shingles: Vec<&[&str]> = if tokens.len() <= SHINGLE_SIZE {
+        vec![&tokens[..]]
+    } else {
+        tokens.windows(SHINGLE_SIZE).collect()
+    };
+
+    let mut hashes: Vec<u64> = shingles
+        .iter()
+        .map(|shingle| fnv1a_64(shingle.join(" ").as_bytes()))
+        .collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes.truncate(SKETCH_SIZE);
+    hashes
+}
+
+/// Estimate Jaccard similarity between two bottom-k sketches as the fraction
+/// of the `k` smallest values of their merged multiset that appear in both.
+pub fn jaccard_estimate(a: &[u64], b: &[u64]) -> f64 {
+    let mut merged: Vec<u64> = a.iter().chain(b.iter()).copied().collect();
+    merged.sort_unstable();
+    merged.dedup();
+    merged.truncate(SKETCH_SIZE);
+    if merged.is_empty() {
+        return 0.0;
+    }
+
+    let matches = merged
+        .iter()
+        .filter(|v| a.contains(v) && b.contains(v))
+        .count();
+    matches as f64 / merged.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_code_is_fully_similar() {
+        let code = "const a = 1; function f(x) { return x + a; }";
+        let a = minhash_sketch(code);
+        let b = minhash_sketch(code);
+        assert_eq!(jaccard_estimate(&a, &b), 1.0);
+    }
+
+    #[test]
+    fn test_unrelated_code_is_not_similar() {
+        let a = minhash_sketch("const a = 1; function f(x) { return x + a; }");
+        let b = minhash_sketch("export default class Widget extends Base { render() {} }");
+        assert!(jaccard_estimate(&a, &b) < 0.5);
+    }
+}